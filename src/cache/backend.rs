@@ -0,0 +1,92 @@
+use crate::{cache::sqlite::SqliteCacheStore, cache::Cache, common::*, config::CacheBackend};
+
+/// Backend-agnostic storage for the upload cache.
+///
+/// `process_read_cache` and other cache consumers go through this trait so
+/// they don't need to know whether items are backed by the single JSON
+/// blob (the default, kept for compatibility) or by the SQLite store used
+/// for very large collections.
+pub trait CacheStore {
+    /// Returns a clone of the item stored at `index`, if any.
+    fn get(&self, index: &str) -> Option<CacheItem>;
+
+    /// Inserts or replaces the item stored at `index`.
+    fn insert(&mut self, index: String, item: CacheItem);
+
+    /// Iterates over all `(index, item)` pairs in storage order.
+    fn iter(&self) -> Box<dyn Iterator<Item = (String, CacheItem)> + '_>;
+
+    /// Iterates over all `(index, item)` pairs with non-numeric indices
+    /// first (sorted as strings), followed by numeric indices in
+    /// ascending order.
+    fn sorted_iter(&self) -> Box<dyn Iterator<Item = (String, CacheItem)> + '_>;
+
+    /// Persists any buffered changes to durable storage.
+    fn flush(&mut self) -> Result<()>;
+}
+
+impl CacheStore for Cache {
+    fn get(&self, index: &str) -> Option<CacheItem> {
+        self.items.get(index).cloned()
+    }
+
+    fn insert(&mut self, index: String, item: CacheItem) {
+        self.items.insert(index, item);
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (String, CacheItem)> + '_> {
+        Box::new(self.items.iter().map(|(k, v)| (k.clone(), v.clone())))
+    }
+
+    fn sorted_iter(&self) -> Box<dyn Iterator<Item = (String, CacheItem)> + '_> {
+        // `sorted_iter` only takes `&self`, so sort a copy of the entries
+        // rather than the map in place.
+        let mut items: Vec<(String, CacheItem)> =
+            self.items.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        items.sort_by(|(key_a, _), (key_b, _)| sort_cache_keys(key_a, key_b));
+        Box::new(items.into_iter())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        // move all non-numeric keys to the beginning and sort as strings,
+        // sort numeric keys as integers, before writing the file out
+        self.items
+            .sort_by(|key_a, _, key_b, _| sort_cache_keys(key_a, key_b));
+        self.sync_file()
+    }
+}
+
+fn sort_cache_keys(key_a: &str, key_b: &str) -> std::cmp::Ordering {
+    // parse with the same integer width as `SqliteCacheStore::sort_key` so
+    // both backends agree on ordering for indices too large for an `i32`
+    let a = key_a.parse::<i64>();
+    let b = key_b.parse::<i64>();
+
+    if a.is_err() && b.is_err() {
+        // string, string
+        key_a.cmp(key_b)
+    } else if a.is_ok() && b.is_err() {
+        // number, string
+        std::cmp::Ordering::Greater
+    } else if a.is_err() && b.is_ok() {
+        // string, number
+        std::cmp::Ordering::Less
+    } else {
+        // number, number
+        a.unwrap().cmp(&b.unwrap())
+    }
+}
+
+/// Loads the cache at `cache_path` using the backend selected by
+/// `backend` (defaulting to the JSON backend for compatibility with
+/// existing configs that don't set `cache_backend`).
+pub fn load_cache_store(
+    cache_path: &str,
+    backend: Option<CacheBackend>,
+    create: bool,
+) -> Result<Box<dyn CacheStore>> {
+    match backend.unwrap_or_default() {
+        CacheBackend::Json => Ok(Box::new(crate::cache::load_cache(cache_path, create)?)),
+        CacheBackend::Sqlite => Ok(Box::new(SqliteCacheStore::open(cache_path, create)?)),
+    }
+}
@@ -0,0 +1,202 @@
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{cache::backend::CacheStore, common::*};
+
+/// Cache backend backed by a SQLite database, one row per item, intended
+/// for collections large enough (50k+ items) that rewriting the whole
+/// JSON blob on every change becomes a bottleneck.
+///
+/// Writes are buffered in memory and applied as a single batch of
+/// upserts inside one transaction on `flush`, so a read-cache run that
+/// touches every item still only pays for one transaction commit.
+pub struct SqliteCacheStore {
+    conn: Connection,
+    pending: Vec<(String, CacheItem)>,
+}
+
+impl SqliteCacheStore {
+    pub fn open(cache_path: &str, create: bool) -> Result<SqliteCacheStore> {
+        if !create && !std::path::Path::new(cache_path).exists() {
+            return Err(anyhow!("Cache file '{}' does not exist", cache_path));
+        }
+
+        let conn = Connection::open(cache_path)?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS cache_items (
+                idx             TEXT PRIMARY KEY,
+                name            TEXT NOT NULL,
+                image_hash      TEXT,
+                image_link      TEXT,
+                metadata_hash   TEXT,
+                metadata_link   TEXT,
+                on_chain        INTEGER NOT NULL,
+                animation_hash  TEXT,
+                animation_link  TEXT,
+                sort_key        TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        Ok(SqliteCacheStore {
+            conn,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Sortable key that reproduces the JSON backend's ordering: non-numeric
+    /// indices first (sorted as strings), then numeric indices in ascending
+    /// order.
+    fn sort_key(index: &str) -> String {
+        match index.parse::<i64>() {
+            // flip the sign bit so negative indices also compare correctly
+            // as zero-padded text
+            Ok(n) => format!("1:{:020}", (n as u64) ^ (1u64 << 63)),
+            Err(_) => format!("0:{}", index),
+        }
+    }
+
+    fn row_to_item(
+        name: String,
+        image_hash: Option<String>,
+        image_link: Option<String>,
+        metadata_hash: Option<String>,
+        metadata_link: Option<String>,
+        on_chain: bool,
+        animation_hash: Option<String>,
+        animation_link: Option<String>,
+    ) -> CacheItem {
+        CacheItem {
+            name,
+            image_hash: image_hash.unwrap_or_default(),
+            image_link: image_link.unwrap_or_default(),
+            metadata_hash: metadata_hash.unwrap_or_default(),
+            metadata_link: metadata_link.unwrap_or_default(),
+            on_chain,
+            animation_hash,
+            animation_link,
+        }
+    }
+}
+
+impl CacheStore for SqliteCacheStore {
+    fn get(&self, index: &str) -> Option<CacheItem> {
+        if let Some((_, item)) = self.pending.iter().rev().find(|(idx, _)| idx == index) {
+            return Some(item.clone());
+        }
+
+        self.conn
+            .query_row(
+                "SELECT name, image_hash, image_link, metadata_hash, metadata_link, on_chain,
+                        animation_hash, animation_link
+                 FROM cache_items WHERE idx = ?1",
+                params![index],
+                |row| {
+                    Ok(Self::row_to_item(
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                        row.get(6)?,
+                        row.get(7)?,
+                    ))
+                },
+            )
+            .optional()
+            .ok()
+            .flatten()
+    }
+
+    fn insert(&mut self, index: String, item: CacheItem) {
+        self.pending.push((index, item));
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (String, CacheItem)> + '_> {
+        self.sorted_iter()
+    }
+
+    fn sorted_iter(&self) -> Box<dyn Iterator<Item = (String, CacheItem)> + '_> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT idx, name, image_hash, image_link, metadata_hash, metadata_link,
+                        on_chain, animation_hash, animation_link
+                 FROM cache_items ORDER BY sort_key ASC",
+            )
+            .expect("failed to prepare sorted cache query");
+
+        let rows = stmt
+            .query_map([], |row| {
+                let index: String = row.get(0)?;
+                let item = Self::row_to_item(
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                    row.get(6)?,
+                    row.get(7)?,
+                    row.get(8)?,
+                );
+                Ok((index, item))
+            })
+            .expect("failed to read cache rows")
+            .filter_map(|row| row.ok())
+            .collect::<Vec<_>>();
+
+        // overlay unflushed inserts so they are visible before `flush`, as
+        // they are through `get`
+        let mut merged: std::collections::BTreeMap<(String, String), CacheItem> = rows
+            .into_iter()
+            .map(|(index, item)| ((Self::sort_key(&index), index), item))
+            .collect();
+        for (index, item) in &self.pending {
+            merged.insert((Self::sort_key(index), index.clone()), item.clone());
+        }
+
+        Box::new(merged.into_iter().map(|((_, index), item)| (index, item)))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let tx = self.conn.transaction()?;
+        for (index, item) in self.pending.drain(..) {
+            let sort_key = Self::sort_key(&index);
+            tx.execute(
+                "INSERT INTO cache_items
+                    (idx, name, image_hash, image_link, metadata_hash, metadata_link,
+                     on_chain, animation_hash, animation_link, sort_key)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                 ON CONFLICT(idx) DO UPDATE SET
+                    name = excluded.name,
+                    image_hash = excluded.image_hash,
+                    image_link = excluded.image_link,
+                    metadata_hash = excluded.metadata_hash,
+                    metadata_link = excluded.metadata_link,
+                    on_chain = excluded.on_chain,
+                    animation_hash = excluded.animation_hash,
+                    animation_link = excluded.animation_link,
+                    sort_key = excluded.sort_key",
+                params![
+                    index,
+                    item.name,
+                    item.image_hash,
+                    item.image_link,
+                    item.metadata_hash,
+                    item.metadata_link,
+                    item.on_chain,
+                    item.animation_hash,
+                    item.animation_link,
+                    sort_key,
+                ],
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+}
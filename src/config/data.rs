@@ -15,10 +15,70 @@ use mpl_candy_machine::{
     HiddenSettings as CandyHiddenSettings, WhitelistMintMode as CandyWhitelistMintMode,
     WhitelistMintSettings as CandyWhitelistMintSettings,
 };
+use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::config::errors::*;
 
+/// Wraps an auth token/credential so it never leaks into `Debug` output or
+/// error logs (note the `error!("{:?}", ...)` calls throughout the
+/// read-cache/upload modules).
+///
+/// This wraps `secrecy::SecretString` rather than deriving from it
+/// directly: `Secret<String>` only implements `Clone` via secrecy's
+/// `CloneableSecret` marker, which isn't implemented for `String`, so
+/// `ConfigData`/`PinataConfig` (both `#[derive(Clone)]`) wouldn't compile
+/// otherwise.
+pub struct Secret(SecretString);
+
+impl Secret {
+    pub fn new(value: String) -> Secret {
+        Secret(SecretString::new(value))
+    }
+
+    /// Returns the raw secret. Use only at the actual HTTP call sites that
+    /// need to send the token.
+    pub fn expose_secret(&self) -> &str {
+        self.0.expose_secret()
+    }
+}
+
+impl Clone for Secret {
+    fn clone(&self) -> Secret {
+        Secret::new(self.0.expose_secret().to_owned())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret(\"[REDACTED]\")")
+    }
+}
+
+impl Serialize for Secret {
+    // A serialized config can be written back to disk as-is, so this must
+    // re-emit the real value —
+    // redacting here would silently clobber the token on disk and the next
+    // run would try to authenticate with the literal string "[REDACTED]".
+    // Redaction is handled by `Debug` instead, which is what logging uses.
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.expose_secret())
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        Ok(Secret::new(value))
+    }
+}
+
 pub struct SugarConfig {
     pub keypair: Keypair,
     pub rpc_url: String,
@@ -73,13 +133,33 @@ pub struct ConfigData {
 
     pub aws_config: Option<AwsConfig>,
 
-    #[serde(serialize_with = "to_option_string")]
-    pub nft_storage_auth_token: Option<String>,
+    pub nft_storage_auth_token: Option<Secret>,
 
     #[serde(serialize_with = "to_option_string")]
     pub shdw_storage_account: Option<String>,
 
     pub pinata_config: Option<PinataConfig>,
+
+    /// Fallback cap on concurrent asset verification requests used when
+    /// `pinata_config.parallel_limit` is not set.
+    pub parallel_limit: Option<u16>,
+
+    /// Storage backend for the upload cache. Defaults to the JSON backend
+    /// when not set, so existing configs keep working unchanged.
+    pub cache_backend: Option<CacheBackend>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheBackend {
+    Json,
+    Sqlite,
+}
+
+impl Default for CacheBackend {
+    fn default() -> CacheBackend {
+        CacheBackend::Json
+    }
 }
 
 pub fn to_string<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
@@ -431,7 +511,7 @@ impl AwsConfig {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PinataConfig {
-    pub jwt: String,
+    pub jwt: Secret,
     pub api_gateway: String,
     pub content_gateway: String,
     pub parallel_limit: Option<u16>,
@@ -440,7 +520,7 @@ pub struct PinataConfig {
 impl PinataConfig {
     pub fn new(jwt: String, api_gateway: String, content_gateway: String) -> PinataConfig {
         PinataConfig {
-            jwt,
+            jwt: Secret::new(jwt),
             api_gateway,
             content_gateway,
             parallel_limit: None,
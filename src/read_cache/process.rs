@@ -1,16 +1,28 @@
-use std::sync::{atomic::AtomicBool, Arc};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use console::style;
+use futures::stream::{self, StreamExt};
 use mpl_candy_machine_core::replace_patterns;
-use reqwest::IntoUrl;
+use rand::Rng;
+use reqwest::{IntoUrl, StatusCode};
 
 use crate::{
-    cache::load_cache,
+    cache::{load_cache_store, CacheStore},
     common::*,
     config::{get_config_data, HiddenSettings},
     validate::format::Metadata,
 };
 
+/// Default number of concurrent asset verifications when neither the
+/// pinata config nor the top-level config specify a `parallel_limit`.
+const DEFAULT_PARALLEL_LIMIT: usize = 16;
+
 pub struct ReadCacheArgs {
     pub config: String,
     pub cache: String,
@@ -23,91 +35,332 @@ pub struct AssetType {
     pub animation: Vec<isize>,
 }
 
+/// Prints the run summary: every item has an image and metadata, so
+/// `image`/`metadata` report how many were verified, while `animation` is
+/// optional per item, so it's reported as found vs. missing.
+fn print_asset_summary(asset_type: &AssetType) {
+    let processed = asset_type.image.len();
+    let animation_found = asset_type.animation.len();
+    let animation_missing = processed.saturating_sub(animation_found);
+
+    println!(
+        "\n{} images verified, {} metadata verified, {} animation assets found, {} missing",
+        processed,
+        asset_type.metadata.len(),
+        animation_found,
+        animation_missing
+    );
+}
+
 pub async fn process_read_cache(args: ReadCacheArgs) -> Result<()> {
     let config_data = get_config_data(&args.config)?;
 
-    // creates/loads the cache
-    let mut cache = load_cache(&args.cache, true)?;
+    // creates/loads the cache, using whichever backend the config selects
+    // (JSON by default, SQLite for very large collections)
+    let mut cache = load_cache_store(&args.cache, config_data.cache_backend, true)?;
+
+    let http_client = reqwest::Client::builder()
+        .gzip(true)
+        .http2_adaptive_window(true)
+        .build()?;
+
+    let parallel_limit = config_data
+        .pinata_config
+        .as_ref()
+        .and_then(|pinata| pinata.parallel_limit)
+        .or(config_data.parallel_limit)
+        .map(|limit| limit as usize)
+        .unwrap_or(DEFAULT_PARALLEL_LIMIT);
 
-    let http_client = reqwest::Client::new();
+    // set instead of returned immediately below, so that a verification
+    // failure still leaves the re-verified items in `cache` flushed to
+    // disk; the error is raised only after that shared flush runs
+    let mut verification_error: Option<anyhow::Error> = None;
 
     if let Some(HiddenSettings { name, uri, .. }) = config_data.hidden_settings {
-        for index in 0..config_data.number {
-            println!(
-                "{} {}Downloading assets",
-                style(format!("[{}/{}]", index, config_data.number))
-                    .bold()
-                    .dim(),
-                ASSETS_EMOJI
-            );
-            let name = replace_patterns(name.clone(), index as usize);
-            let metadata_link = replace_patterns(uri.clone(), index as usize);
-            let metadata_text = http_client.get(&metadata_link).send().await?.text().await?;
-            let metadata: Metadata = match serde_json::from_str(&metadata_text) {
-                Ok(metadata) => metadata,
-                Err(err) => {
-                    let error = anyhow!("Error parsing metadata ({}): {}", &metadata_link, err);
-                    error!("{:?}", error);
-                    return Err(error);
+        let number = config_data.number;
+        let results: Vec<Result<(u64, CacheItem)>> = stream::iter(0..number)
+            .map(|index| {
+                let http_client = http_client.clone();
+                let interrupted = args.interrupted.clone();
+                let name = replace_patterns(name.clone(), index as usize);
+                let metadata_link = replace_patterns(uri.clone(), index as usize);
+
+                async move {
+                    println!(
+                        "{} {}Downloading assets",
+                        style(format!("[{}/{}]", index, number)).bold().dim(),
+                        ASSETS_EMOJI
+                    );
+                    let metadata_text = fetch_text_with_retry(
+                        &http_client,
+                        &metadata_link,
+                        &interrupted,
+                        RetryConfig::default(),
+                    )
+                    .await?;
+                    let metadata: Metadata = match serde_json::from_str(&metadata_text) {
+                        Ok(metadata) => metadata,
+                        Err(err) => {
+                            let error =
+                                anyhow!("Error parsing metadata ({}): {}", &metadata_link, err);
+                            error!("{:?}", error);
+                            return Err(error);
+                        }
+                    };
+                    if metadata.name != name {
+                        let error = anyhow!(
+                            "Error checking metadata ({}): Invalid name {} expected {}",
+                            metadata_link,
+                            metadata.name,
+                            name
+                        );
+                        error!("{:?}", error);
+                        return Err(error);
+                    }
+                    let metadata_hash = encode_text(&metadata_text)?;
+                    let image_link = metadata.image;
+                    let image_hash = encode_url_with_retry(
+                        &http_client,
+                        &image_link,
+                        &interrupted,
+                        RetryConfig::default(),
+                    )
+                    .await?;
+
+                    let animation_link = metadata.animation_url;
+                    let animation_hash = match &animation_link {
+                        Some(link) => Some(
+                            encode_url_with_retry(
+                                &http_client,
+                                link,
+                                &interrupted,
+                                RetryConfig::default(),
+                            )
+                            .await?,
+                        ),
+                        None => None,
+                    };
+
+                    Ok((
+                        index,
+                        CacheItem {
+                            name,
+                            image_hash,
+                            image_link,
+                            metadata_hash,
+                            metadata_link,
+                            on_chain: false, // TODO: think
+                            animation_hash,
+                            animation_link,
+                        },
+                    ))
                 }
-            };
-            if metadata.name != name {
-                let error = anyhow!(
-                    "Error checking metadata ({}): Invalid name {} expected {}",
-                    metadata_link,
-                    metadata.name,
-                    name
-                );
-                error!("{:?}", error);
-                return Err(error);
+            })
+            .buffer_unordered(parallel_limit)
+            .collect()
+            .await;
+
+        let mut asset_type = AssetType {
+            image: Vec::new(),
+            metadata: Vec::new(),
+            animation: Vec::new(),
+        };
+
+        for result in results {
+            let (index, cache_item) = result?;
+            let index = index as isize;
+
+            asset_type.image.push(index);
+            asset_type.metadata.push(index);
+            if cache_item.animation_link.is_some() {
+                asset_type.animation.push(index);
             }
-            let metadata_hash = encode_text(&metadata_text)?;
-            let image_link = metadata.image;
-            let image_hash = encode_url(&http_client, &image_link).await?;
-
-            cache.items.insert(
-                index.to_string(),
-                CacheItem {
-                    name,
-                    image_hash,
-                    image_link,
-                    metadata_hash,
-                    metadata_link,
-                    on_chain: false,      // TODO: think
-                    animation_hash: None, // TODO
-                    animation_link: None,
-                },
-            );
+
+            cache.insert(index.to_string(), cache_item);
         }
+
+        print_asset_summary(&asset_type);
     } else {
-        let error = anyhow!("Only hidden type of config is supported");
-        error!("{:?}", error);
-        return Err(error);
-    }
+        // no `hidden_settings`: every item has its own metadata URI already
+        // recorded in the cache from the upload step, so verify each one
+        // against its own link instead of deriving a templated link
+        let existing_items: Vec<(String, CacheItem)> = cache.iter().collect();
+
+        if existing_items.is_empty() {
+            let error = anyhow!(
+                "No cache items to verify; run the upload step first for a non-hidden config"
+            );
+            error!("{:?}", error);
+            return Err(error);
+        }
+
+        let total = existing_items.len();
+        let results: Vec<Result<(String, CacheItem, Vec<String>)>> =
+            stream::iter(existing_items.into_iter().enumerate())
+                .map(|(position, (index, existing_item))| {
+                    let http_client = http_client.clone();
+                    let interrupted = args.interrupted.clone();
+
+                    async move {
+                        println!(
+                            "{} {}Downloading assets",
+                            style(format!("[{}/{}]", position, total)).bold().dim(),
+                            ASSETS_EMOJI
+                        );
+
+                        // a fetch/parse failure for one item shouldn't abort
+                        // verification of the rest, so collect it as a
+                        // mismatch instead of propagating it, unless the
+                        // whole run is being interrupted
+                        let verified: Result<(CacheItem, Vec<String>)> = async {
+                            let mut mismatches = Vec::new();
+                            let metadata_link = existing_item.metadata_link.clone();
+                            let metadata_text = fetch_text_with_retry(
+                                &http_client,
+                                &metadata_link,
+                                &interrupted,
+                                RetryConfig::default(),
+                            )
+                            .await?;
+                            let metadata: Metadata = serde_json::from_str(&metadata_text)
+                                .map_err(|err| {
+                                    anyhow!(
+                                        "Error parsing metadata ({}): {}",
+                                        &metadata_link,
+                                        err
+                                    )
+                                })?;
+
+                            if metadata.name != existing_item.name {
+                                mismatches.push(format!(
+                                    "name mismatch: expected {} got {}",
+                                    existing_item.name, metadata.name
+                                ));
+                            }
+
+                            let metadata_hash = encode_text(&metadata_text)?;
+                            if metadata_hash != existing_item.metadata_hash {
+                                mismatches.push("metadata hash mismatch".to_string());
+                            }
 
-    // move all non-numeric keys to the beginning and sort as strings
-    // sort numeric keys as integers
-    cache
-        .items
-        .sort_by(|key_a, _, key_b, _| -> std::cmp::Ordering {
-            let a = key_a.parse::<i32>();
-            let b = key_b.parse::<i32>();
-
-            if a.is_err() && b.is_err() {
-                // string, string
-                key_a.cmp(key_b)
-            } else if a.is_ok() && b.is_err() {
-                // number, string
-                std::cmp::Ordering::Greater
-            } else if a.is_err() && b.is_ok() {
-                // string, number
-                std::cmp::Ordering::Less
+                            let image_hash = encode_url_with_retry(
+                                &http_client,
+                                &metadata.image,
+                                &interrupted,
+                                RetryConfig::default(),
+                            )
+                            .await?;
+                            if image_hash != existing_item.image_hash {
+                                mismatches.push("image hash mismatch".to_string());
+                            }
+
+                            let animation_hash = match &metadata.animation_url {
+                                Some(link) => Some(
+                                    encode_url_with_retry(
+                                        &http_client,
+                                        link,
+                                        &interrupted,
+                                        RetryConfig::default(),
+                                    )
+                                    .await?,
+                                ),
+                                None => None,
+                            };
+                            if animation_hash != existing_item.animation_hash {
+                                mismatches.push("animation hash mismatch".to_string());
+                            }
+
+                            let item = CacheItem {
+                                name: metadata.name,
+                                image_hash,
+                                image_link: metadata.image,
+                                metadata_hash,
+                                metadata_link,
+                                on_chain: existing_item.on_chain,
+                                animation_hash,
+                                animation_link: metadata.animation_url,
+                            };
+
+                            Ok((item, mismatches))
+                        }
+                        .await;
+
+                        let (item, mismatches) = match verified {
+                            Ok(result) => result,
+                            Err(err) if interrupted.load(Ordering::SeqCst) => return Err(err),
+                            Err(err) => {
+                                error!("{:?}", err);
+                                (
+                                    CacheItem {
+                                        name: existing_item.name.clone(),
+                                        image_hash: existing_item.image_hash.clone(),
+                                        image_link: existing_item.image_link.clone(),
+                                        metadata_hash: existing_item.metadata_hash.clone(),
+                                        metadata_link: existing_item.metadata_link.clone(),
+                                        on_chain: existing_item.on_chain,
+                                        animation_hash: existing_item.animation_hash.clone(),
+                                        animation_link: existing_item.animation_link.clone(),
+                                    },
+                                    vec![format!("failed to verify: {}", err)],
+                                )
+                            }
+                        };
+
+                        Ok((index, item, mismatches))
+                    }
+                })
+                .buffer_unordered(parallel_limit)
+                .collect()
+                .await;
+
+        let mut asset_type = AssetType {
+            image: Vec::new(),
+            metadata: Vec::new(),
+            animation: Vec::new(),
+        };
+        let mut failures = Vec::new();
+
+        for (position, result) in results.into_iter().enumerate() {
+            let (index, item, mismatches) = result?;
+            let position = position as isize;
+
+            if mismatches.is_empty() {
+                asset_type.image.push(position);
+                asset_type.metadata.push(position);
+                if item.animation_link.is_some() {
+                    asset_type.animation.push(position);
+                }
             } else {
-                // number, number
-                a.unwrap().cmp(&b.unwrap())
+                error!("Item {} failed verification: {}", index, mismatches.join(", "));
+                failures.push(index.clone());
             }
-        });
-    cache.sync_file()?;
+
+            cache.insert(index, item);
+        }
+
+        print_asset_summary(&asset_type);
+
+        if !failures.is_empty() {
+            let error = anyhow!(
+                "{} item(s) failed verification: {}",
+                failures.len(),
+                failures.join(", ")
+            );
+            error!("{:?}", error);
+            verification_error = Some(error);
+        }
+    }
+
+    // non-numeric keys sort before numeric keys, which sort as integers;
+    // each backend applies this ordering itself (in-memory for JSON, via
+    // `ORDER BY` for SQLite), so we just persist the new items here
+    // regardless of whether verification reported mismatches above
+    cache.flush()?;
+
+    if let Some(error) = verification_error {
+        return Err(error);
+    }
 
     Ok(())
 }
@@ -123,7 +376,6 @@ pub fn encode_text(text: &str) -> Result<String> {
 
 pub async fn encode_url(http_client: &HttpClient, url: impl IntoUrl) -> Result<String> {
     use data_encoding::HEXLOWER;
-    use futures::StreamExt;
     use ring::digest::{Context, SHA256};
 
     let mut input = http_client.get(url).send().await?.bytes_stream();
@@ -134,3 +386,212 @@ pub async fn encode_url(http_client: &HttpClient, url: impl IntoUrl) -> Result<S
 
     Ok(HEXLOWER.encode(context.finish().as_ref()))
 }
+
+/// Retrying variant of [`encode_url`] used by [`process_read_cache`], where
+/// a transient network blip partway through a large cache rebuild shouldn't
+/// abort the whole run. Kept separate from `encode_url` (a `pub` fn with
+/// other callers elsewhere in the crate) so those callers don't have to
+/// pass an `interrupted`/`RetryConfig` they don't have.
+async fn encode_url_with_retry(
+    http_client: &HttpClient,
+    url: impl IntoUrl,
+    interrupted: &Arc<AtomicBool>,
+    retry_config: RetryConfig,
+) -> Result<String> {
+    use data_encoding::HEXLOWER;
+    use ring::digest::{Context, SHA256};
+
+    let url = url.into_url()?;
+
+    with_retry(interrupted, retry_config, || {
+        let url = url.clone();
+        async move {
+            let response = http_client.get(url.clone()).send().await?;
+            let response = ok_or_status_error(response).await?;
+
+            // hashing consumes the byte stream, so a retry always restarts
+            // the digest from scratch rather than resuming mid-stream
+            let mut context = Context::new(&SHA256);
+            let mut stream = response.bytes_stream();
+            while let Some(part) = stream.next().await {
+                context.update(&part.map_err(FetchError::from)?);
+            }
+
+            Ok(HEXLOWER.encode(context.finish().as_ref()))
+        }
+    })
+    .await
+}
+
+/// Fetches `url` as text, retrying transient failures. Used for the
+/// metadata fetch in [`process_read_cache`]; non-retryable failures (404,
+/// 400, a body read error other than a timeout) are surfaced immediately.
+/// The body is decoded lossily, so invalid UTF-8 is not an error.
+async fn fetch_text_with_retry(
+    http_client: &HttpClient,
+    url: &str,
+    interrupted: &Arc<AtomicBool>,
+    retry_config: RetryConfig,
+) -> Result<String> {
+    with_retry(interrupted, retry_config, || async move {
+        let response = http_client.get(url).send().await?;
+        let response = ok_or_status_error(response).await?;
+        response.text().await.map_err(FetchError::from)
+    })
+    .await
+}
+
+/// Default number of attempts after the initial try before a retryable
+/// fetch error is surfaced to the caller.
+const DEFAULT_MAX_RETRIES: u32 = 5;
+/// Base delay for the `base * 2^attempt` backoff calculation.
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+/// Upper bound on any single backoff delay, `Retry-After` hints included.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Granularity at which a backoff wait checks `interrupted`.
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryConfig {
+    pub max_retries: u32,
+}
+
+impl Default for RetryConfig {
+    fn default() -> RetryConfig {
+        RetryConfig {
+            max_retries: DEFAULT_MAX_RETRIES,
+        }
+    }
+}
+
+/// A fetch failure annotated with whether it's worth retrying and, when
+/// the server told us, how long to wait before trying again.
+struct FetchError {
+    error: anyhow::Error,
+    retryable: bool,
+    retry_after: Option<Duration>,
+}
+
+impl FetchError {
+    fn fatal(error: anyhow::Error) -> FetchError {
+        FetchError {
+            error,
+            retryable: false,
+            retry_after: None,
+        }
+    }
+
+    fn retryable(error: anyhow::Error, retry_after: Option<Duration>) -> FetchError {
+        FetchError {
+            error,
+            retryable: true,
+            retry_after,
+        }
+    }
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(err: reqwest::Error) -> FetchError {
+        if err.is_connect() || err.is_timeout() {
+            FetchError::retryable(err.into(), None)
+        } else {
+            FetchError::fatal(err.into())
+        }
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 408 | 429 | 500 | 502 | 503 | 504)
+}
+
+fn retry_after_header(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Turns a non-2xx response into a `FetchError`, classifying whether the
+/// status is worth retrying and picking up a `Retry-After` hint for
+/// 429/503 when the server sends one.
+async fn ok_or_status_error(response: reqwest::Response) -> Result<reqwest::Response, FetchError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let url = response.url().to_string();
+    let error = anyhow!("Request to {} failed with status {}", url, status);
+
+    if !is_retryable_status(status) {
+        return Err(FetchError::fatal(error));
+    }
+
+    let retry_after = match status.as_u16() {
+        429 | 503 => retry_after_header(&response),
+        _ => None,
+    };
+
+    Err(FetchError::retryable(error, retry_after))
+}
+
+fn backoff_delay(attempt: u32, retry_after: Option<Duration>) -> Duration {
+    let delay = retry_after.unwrap_or_else(|| {
+        let exponential = BASE_BACKOFF * 2u32.saturating_pow(attempt);
+        let jitter = Duration::from_millis(rand::thread_rng().gen_range(0..250));
+        exponential + jitter
+    });
+
+    delay.min(MAX_BACKOFF)
+}
+
+/// Sleeps for `delay` in short slices so a Ctrl-C between retries
+/// (`interrupted` flipping to `true`) stops the wait immediately instead
+/// of blocking for the full backoff.
+async fn interruptible_sleep(delay: Duration, interrupted: &Arc<AtomicBool>) {
+    let mut remaining = delay;
+    while remaining > Duration::ZERO {
+        if interrupted.load(Ordering::SeqCst) {
+            return;
+        }
+        let step = remaining.min(INTERRUPT_POLL_INTERVAL);
+        tokio::time::sleep(step).await;
+        remaining = remaining.saturating_sub(step);
+    }
+}
+
+/// Retries `operation` up to `retry_config.max_retries` times with
+/// exponential backoff (`base * 2^attempt` plus jitter, capped at
+/// [`MAX_BACKOFF`]), honoring a `Retry-After` hint when the operation
+/// reports one. Non-retryable errors and an `interrupted` flag flipping
+/// mid-wait both stop the loop immediately.
+async fn with_retry<T, F, Fut>(
+    interrupted: &Arc<AtomicBool>,
+    retry_config: RetryConfig,
+    mut operation: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = std::result::Result<T, FetchError>>,
+{
+    let mut attempt = 0;
+
+    loop {
+        if interrupted.load(Ordering::SeqCst) {
+            return Err(anyhow!("Interrupted"));
+        }
+
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(err) if err.retryable && attempt < retry_config.max_retries => {
+                attempt += 1;
+                interruptible_sleep(backoff_delay(attempt, err.retry_after), interrupted).await;
+            }
+            Err(err) => return Err(err.error),
+        }
+    }
+}
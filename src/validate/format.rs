@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+/// The subset of the off-chain NFT metadata JSON standard that the
+/// read-cache/verify flow checks against on-chain and cache data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Metadata {
+    pub name: String,
+    pub image: String,
+    /// Optional animation/video/audio/3D asset, verified the same way as
+    /// `image` when present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub animation_url: Option<String>,
+}